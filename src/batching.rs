@@ -0,0 +1,85 @@
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Configures how long and how large an actor lets a bulk of records grow before handing it to
+/// the storage backend.
+///
+/// The bulk is flushed as soon as either limit is reached, whichever comes first. This lets a
+/// steady trickle of records still be written in reasonably sized batches (bounded by
+/// `max_delay`), while a burst of records is not collected into an unboundedly large `Vec`
+/// (bounded by `max_records`).
+#[derive(Debug, Clone, Copy)]
+pub struct BatchPolicy {
+    /// Flush the bulk as soon as it holds this many records.
+    pub max_records: usize,
+    /// Flush the bulk at the latest this long after the first record has been buffered.
+    pub max_delay: Duration,
+}
+
+impl Default for BatchPolicy {
+    /// Flushes as soon as no more records are immediatly available, without waiting for more to
+    /// arrive, and without any limit on the number of records in a bulk.
+    fn default() -> Self {
+        BatchPolicy {
+            max_records: usize::MAX,
+            max_delay: Duration::ZERO,
+        }
+    }
+}
+
+/// A command which may or may not carry a record to fold into a bulk.
+///
+/// Implemented by the command enum of each actor (`recorder::Command`, `try_recorder::Command`)
+/// so both can share [`fill_bulk`] instead of forking its batching logic.
+pub(crate) trait Batched: Sized {
+    /// The kind of record carried by the variant which should be folded into a bulk.
+    type Record;
+
+    /// Extracts the record out of a command which carries one, handing the command itself back
+    /// otherwise.
+    fn into_record(self) -> Result<Self::Record, Self>;
+}
+
+/// Something an actor can receive its next command from.
+///
+/// Implemented for every receiver type an actor can be backed by, so [`fill_bulk`] does not need
+/// to know whether it is draining a bounded or unbounded channel.
+pub(crate) trait Recv<C> {
+    async fn recv(&mut self) -> Option<C>;
+}
+
+/// Buffers commands carrying a record into `bulk` (which already holds at least one record)
+/// until either `policy.max_records` is reached or `policy.max_delay` has elapsed since the first
+/// record was buffered, whichever comes first. Shared between [`crate::Recorder`]'s and
+/// [`crate::TryRecorder`]'s actors, so their batching behavior can not drift apart.
+///
+/// Returns the next command to process, if one was already received while waiting for `bulk` to
+/// fill up.
+pub(crate) async fn fill_bulk<C, R>(
+    bulk: &mut Vec<C::Record>,
+    policy: &BatchPolicy,
+    receiver: &mut R,
+) -> Option<C>
+where
+    C: Batched,
+    R: Recv<C>,
+{
+    let deadline = sleep(policy.max_delay);
+    tokio::pin!(deadline);
+    loop {
+        if bulk.len() >= policy.max_records {
+            return None;
+        }
+        tokio::select! {
+            biased;
+            command = receiver.recv() => match command {
+                Some(command) => match command.into_record() {
+                    Ok(record) => bulk.push(record),
+                    Err(other) => return Some(other),
+                },
+                None => return None,
+            },
+            _ = &mut deadline => return None,
+        }
+    }
+}
@@ -1,159 +1,430 @@
-use crate::Storage;
-use std::{fmt::Formatter, future::Future};
-use tokio::{
-    sync::{mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender}, oneshot},
-    task::JoinHandle,
-};
-
-/// Persists records asynchronously.
-///
-/// You may want to use this instead of directly calling your persistence backend if you do not want
-/// to wait for the record to be persisted, in the handler which created the record. To achieve this
-/// Recoder spawns an actor to which all records are sent immediatly. The actor when uses the
-/// [`Storage`] trait to talk to your persistence backend.
-///
-/// Recorder takes ownership of an actor and the green thread it is running in.
-pub struct Recorder<T: Storage> {
-    /// We need the handle to make sure we join the actor before our recorder goes out of scope.
-    join_handle: JoinHandle<T>,
-    /// We choose an unbounded sender since we want to talk from sync to async code without waiting
-    /// for the persistence backend to catch up.
-    sender: UnboundedSender<Command<T>>,
-}
-
-impl<T> Recorder<T>
-where
-    T: Storage + 'static + Send,
-    T::Record: Send,
-    T::Query: Send,
-{
-    pub async fn new(storage: T) -> Self {
-        let (sender, receiver) = unbounded_channel();
-        let actor = Actor::new(storage, receiver);
-        let join_handle = tokio::spawn(actor.run());
-        Self {
-            join_handle,
-            sender,
-        }
-    }
-
-    pub fn with_lazy_storage(storage: impl Future<Output=T> + Send + 'static) -> Self {
-        let (sender, receiver) = unbounded_channel();
-        let join_handle = tokio::spawn(async {
-            let actor = Actor::new(storage.await, receiver);
-            actor.run().await
-        });
-        Self {
-            join_handle,
-            sender,
-        }
-    }
-
-    /// Sends the record to the internal actor for storage. This interface is fire and forget. It
-    /// will not wait for the record to be actually persisted, just place it in the channel for the
-    /// actor to pick up. This is why this method is both synchronous and non blocking.
-    pub fn save(&self, record: T::Record) {
-        self.sender
-            .send(Command::Save(record))
-            .expect("Receiver must not be closed.")
-    }
-
-    /// Stop accepting new records to save, persist the ones send so far.
-    ///
-    /// Gives back ownership of the underlying storage.
-    pub async fn close(self) -> T {
-        // Close sender, so we stop sending messages and `Actor::run`.
-        drop(self.sender);
-        // Now that actor run nows it should terminate, we wait for it.
-        self.join_handle
-            .await
-            .expect("Recorder actor thread must always be able to join")
-    }
-
-    /// All the records stored in the internal storage.
-    pub async fn records(&self, query: T::Query) -> Vec<T::Record> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender.send(Command::Load(sender, query)).expect("Receiver must not be closed");
-        receiver.await.expect("The sender must not be dropped")
-    }
-}
-
-/// Asynchronously spawned by [`Recorder`] in order to persist records
-struct Actor<T: Storage> {
-    storage: T,
-    receiver: UnboundedReceiver<Command<T>>,
-}
-
-impl<T> Actor<T>
-where
-    T: Storage,
-{
-    pub fn new(storage: T, receiver: UnboundedReceiver<Command<T>>) -> Self {
-        Self { storage, receiver }
-    }
-
-    pub async fn run(mut self) -> T {
-        // If messages come in fast, we do not send them one by one, but rather collect all since
-        // the last call to save in one bulk;
-        let mut bulk = Vec::new();
-        let mut current = self.receiver.recv().await;
-        while let Some(command) = current.take() {
-            let next = match command {
-                Command::Save(record) => {
-                    bulk.push(record);
-                    // Push all immediatly available records into the next bulk, until it would
-                    // block again, or we would have to serve a load command.
-                    let next = loop {
-                        match self.receiver.try_recv() {
-                            Ok(Command::Save(record)) => bulk.push(record),
-                            Ok(other) => break Some(other),
-                            Err(_) => break None,
-                        }
-                    };
-                    self.storage.save(&mut bulk).await;
-                    bulk.clear();
-                    next
-                },
-                Command::Load(sender, query) => {
-                    // Fetch records ...
-                    let records = self.storage.load(query).await;
-                    // ... and answer sender. This might fail, but if the sender is dropped and
-                    // stopped, caring, so do we. Let's drop the result.
-                    let _ = sender.send(records);
-                    // We did not peek ahead, so we do not know the next command.
-                    None
-                },
-            };
-            // Use next or wait for next event
-            current = if next.is_none() {
-                // Wait for the next event, can block. If none this means recorder has been dropped
-                // and we terminate this loop.
-                self.receiver.recv().await
-            } else {
-                // We already know the next event to process, since we had to peek ahead.
-                next
-            };
-        }
-        self.storage
-    }
-}
-
-/// Message send from recorder to actor. Allowes for custom debug implementation lifting the
-/// limitation that `T` has to be `Debug`.
-enum Command<T: Storage> {
-    /// Save record T to the storage backend
-    Save(T::Record),
-    /// Load all records from the storage. Use the sender to return them back to the caller.
-    Load(oneshot::Sender<Vec<T::Record>>, T::Query),
-}
-
-/// Custom implementation of debug for Message, which does not rely on the record type `T` to be
-/// debug itstelf.
-impl<T> std::fmt::Debug for Command<T> where T: Storage {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Command::Save(_) => f.debug_tuple("Save").finish(),
-            Command::Load(..) => f.debug_tuple("Load").finish(),
-        }
-    }
-}
+use crate::{
+    batching::{fill_bulk, Batched, BatchPolicy, Recv},
+    Storage,
+};
+use std::{fmt::Formatter, future::Future};
+use tokio::{
+    sync::{
+        mpsc::{
+            channel, error::TrySendError, unbounded_channel, Receiver, Sender, UnboundedReceiver,
+            UnboundedSender,
+        },
+        oneshot,
+    },
+    task::JoinHandle,
+};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+
+/// Relays everything received from `source` into `sink`, stopping as soon as either end is closed.
+///
+/// Spawned as its own task per [`Recorder::stream`] call, so that a `source` which is produced
+/// into faster than `sink` is drained only ever blocks this task, not the actor feeding `source`.
+async fn relay_to_sink<R: Send + 'static>(
+    mut source: UnboundedReceiver<R>,
+    sink: Sender<R>,
+) {
+    while let Some(record) = source.recv().await {
+        if sink.send(record).await.is_err() {
+            // The consumer of the stream lost interest, no point relaying further records.
+            break;
+        }
+    }
+}
+
+/// Size of the channel used to stream records back to the caller of [`Recorder::stream`]. Bounds
+/// how far the relay task (see [`relay_to_sink`]) may run ahead of a caller which processes the
+/// stream slower than records can be produced. The actor itself is not bounded by it: it sends
+/// into an unbounded channel, where records the relay task has not forwarded yet queue up.
+const STREAM_BUFFER_SIZE: usize = 16;
+
+/// Persists records asynchronously.
+///
+/// You may want to use this instead of directly calling your persistence backend if you do not want
+/// to wait for the record to be persisted, in the handler which created the record. To achieve this
+/// Recoder spawns an actor to which all records are sent immediatly. The actor when uses the
+/// [`Storage`] trait to talk to your persistence backend.
+///
+/// Recorder takes ownership of an actor and the green thread it is running in.
+pub struct Recorder<T: Storage> {
+    /// We need the handle to make sure we join the actor before our recorder goes out of scope.
+    join_handle: JoinHandle<T>,
+    sender: CommandSender<T>,
+}
+
+impl<T> Recorder<T>
+where
+    T: Storage + 'static + Send,
+    T::Record: Send,
+    T::Query: Send,
+{
+    pub async fn new(storage: T, policy: BatchPolicy) -> Self {
+        let (sender, receiver) = unbounded_channel();
+        let actor = Actor::new(storage, CommandReceiver::Unbounded(receiver), policy);
+        let join_handle = tokio::spawn(actor.run());
+        Self {
+            join_handle,
+            sender: CommandSender::Unbounded(sender),
+        }
+    }
+
+    pub fn with_lazy_storage(
+        storage: impl Future<Output = T> + Send + 'static,
+        policy: BatchPolicy,
+    ) -> Self {
+        let (sender, receiver) = unbounded_channel();
+        let join_handle = tokio::spawn(async move {
+            let actor = Actor::new(storage.await, CommandReceiver::Unbounded(receiver), policy);
+            actor.run().await
+        });
+        Self {
+            join_handle,
+            sender: CommandSender::Unbounded(sender),
+        }
+    }
+
+    /// Creates a recorder backed by a bounded channel holding at most `capacity` commands.
+    ///
+    /// Unlike [`Recorder::new`], which buffers an unlimited number of records in memory if
+    /// [`Storage::save`] can not keep up, this gives the caller a way to exert backpressure
+    /// towards the persistence backend instead of growing memory without bound. Combine this with
+    /// [`Recorder::save_async`] to suspend the caller until there is room in the channel, or
+    /// [`Recorder::try_save`] to find out immediatly whether the record could be enqueued.
+    pub async fn with_capacity(storage: T, capacity: usize, policy: BatchPolicy) -> Self {
+        let (sender, receiver) = channel(capacity);
+        let actor = Actor::new(storage, CommandReceiver::Bounded(receiver), policy);
+        let join_handle = tokio::spawn(actor.run());
+        Self {
+            join_handle,
+            sender: CommandSender::Bounded(sender),
+        }
+    }
+
+    /// Sends the record to the internal actor for storage. This interface is fire and forget. It
+    /// will not wait for the record to be actually persisted, just place it in the channel for the
+    /// actor to pick up. This is why this method is both synchronous and non blocking.
+    ///
+    /// If this recorder has been created using [`Recorder::with_capacity`] and the channel is
+    /// currently full, this method panics. Use [`Recorder::save_async`] or
+    /// [`Recorder::try_save`] instead if the channel might be full.
+    pub fn save(&self, record: T::Record) {
+        match &self.sender {
+            CommandSender::Unbounded(sender) => sender
+                .send(Command::Save(record))
+                .expect("Receiver must not be closed."),
+            CommandSender::Bounded(sender) => sender
+                .try_send(Command::Save(record))
+                .expect("Channel must not be closed or full."),
+        }
+    }
+
+    /// Sends the record to the internal actor for storage, suspending the caller if the channel is
+    /// currently full.
+    ///
+    /// For a recorder created with [`Recorder::new`] or [`Recorder::with_lazy_storage`] this never
+    /// suspends, since those use an unbounded channel. For a recorder created with
+    /// [`Recorder::with_capacity`] this is the way to exert backpressure towards the caller instead
+    /// of growing memory without bound.
+    pub async fn save_async(&self, record: T::Record) {
+        match &self.sender {
+            CommandSender::Unbounded(sender) => sender
+                .send(Command::Save(record))
+                .expect("Receiver must not be closed."),
+            CommandSender::Bounded(sender) => sender
+                .send(Command::Save(record))
+                .await
+                .expect("Receiver must not be closed."),
+        }
+    }
+
+    /// Sends the record to the internal actor for storage, without waiting if the channel is
+    /// currently full. Hands the record back to the caller via [`TrySaveError::Full`] rather than
+    /// blocking or panicking.
+    pub fn try_save(&self, record: T::Record) -> Result<(), TrySaveError<T::Record>> {
+        match &self.sender {
+            CommandSender::Unbounded(sender) => {
+                sender
+                    .send(Command::Save(record))
+                    .expect("Receiver must not be closed.");
+                Ok(())
+            }
+            CommandSender::Bounded(sender) => {
+                sender
+                    .try_send(Command::Save(record))
+                    .map_err(|error| match error {
+                        TrySendError::Full(Command::Save(record)) => TrySaveError::Full(record),
+                        TrySendError::Full(
+                            Command::Load(..) | Command::Stream(..) | Command::Flush(..),
+                        ) => {
+                            unreachable!("Only save commands are sent via try_save")
+                        }
+                        TrySendError::Closed(_) => panic!("Receiver must not be closed."),
+                    })
+            }
+        }
+    }
+
+    /// Stop accepting new records to save, persist the ones send so far.
+    ///
+    /// Gives back ownership of the underlying storage.
+    pub async fn close(self) -> T {
+        // Close sender, so we stop sending messages and `Actor::run`.
+        drop(self.sender);
+        // Now that actor run nows it should terminate, we wait for it.
+        self.join_handle
+            .await
+            .expect("Recorder actor thread must always be able to join")
+    }
+
+    /// All the records stored in the internal storage.
+    pub async fn records(&self, query: T::Query) -> Vec<T::Record> {
+        let (sender, receiver) = oneshot::channel();
+        let command = Command::Load(sender, query);
+        match &self.sender {
+            CommandSender::Unbounded(sender) => {
+                sender.send(command).expect("Receiver must not be closed")
+            }
+            CommandSender::Bounded(sender) => sender
+                .send(command)
+                .await
+                .expect("Receiver must not be closed"),
+        }
+        receiver.await.expect("The sender must not be dropped")
+    }
+
+    /// Waits until every record handed to [`Recorder::save`] (or the other `save*` methods) before
+    /// this call has been persisted, without tearing down the recorder the way [`Recorder::close`]
+    /// does.
+    ///
+    /// Since commands are processed in the order they are sent, and this one is only resolved
+    /// once the bulk it arrived in has been flushed to the storage backend, this gives callers a
+    /// checkpoint: a guarantee that everything sent so far is durable, useful e.g. before
+    /// reporting success to whoever produced the records.
+    pub async fn flush(&self) {
+        let (sender, receiver) = oneshot::channel();
+        let command = Command::Flush(sender);
+        match &self.sender {
+            CommandSender::Unbounded(sender) => {
+                sender.send(command).expect("Receiver must not be closed")
+            }
+            CommandSender::Bounded(sender) => sender
+                .send(command)
+                .await
+                .expect("Receiver must not be closed"),
+        }
+        receiver.await.expect("The sender must not be dropped")
+    }
+
+    /// Streams the records matching `query`, yielding them as the storage backend produces them
+    /// instead of collecting them all into a `Vec` first.
+    ///
+    /// If the underlying storage overrides [`Storage::load_stream`] this can start yielding
+    /// records before the whole query has completed; otherwise it falls back to the default
+    /// implementation, which loads the whole result via [`Storage::load`] before streaming it.
+    /// Either way, records the caller has not consumed yet are buffered in memory in an unbounded
+    /// channel, so a caller slower than the storage backend can end up holding the whole result.
+    pub async fn stream(&self, query: T::Query) -> impl Stream<Item = T::Record> + use<T> {
+        let (sink, source) = channel(STREAM_BUFFER_SIZE);
+        let command = Command::Stream(sink, query);
+        match &self.sender {
+            CommandSender::Unbounded(sender) => {
+                sender.send(command).expect("Receiver must not be closed")
+            }
+            CommandSender::Bounded(sender) => sender
+                .send(command)
+                .await
+                .expect("Receiver must not be closed"),
+        }
+        ReceiverStream::new(source)
+    }
+}
+
+/// Error returned by [`Recorder::try_save`] if the record could not be enqueued immediatly.
+#[derive(Debug)]
+pub enum TrySaveError<R> {
+    /// The channel connecting [`Recorder`] and its actor is currently full. The record which could
+    /// not be enqueued is handed back to the caller, so it is not silently lost.
+    Full(R),
+}
+
+impl<R> std::fmt::Display for TrySaveError<R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrySaveError::Full(_) => write!(f, "Recorder channel is full."),
+        }
+    }
+}
+
+impl<R: std::fmt::Debug> std::error::Error for TrySaveError<R> {}
+
+/// Asynchronously spawned by [`Recorder`] in order to persist records
+struct Actor<T: Storage> {
+    storage: T,
+    receiver: CommandReceiver<T>,
+    policy: BatchPolicy,
+}
+
+impl<T> Actor<T>
+where
+    T: Storage + Send,
+    T::Record: Send + 'static,
+    T::Query: Send,
+{
+    pub fn new(storage: T, receiver: CommandReceiver<T>, policy: BatchPolicy) -> Self {
+        Self {
+            storage,
+            receiver,
+            policy,
+        }
+    }
+
+    pub async fn run(mut self) -> T {
+        // If messages come in fast, we do not send them one by one, but rather collect all since
+        // the last call to save in one bulk;
+        let mut bulk = Vec::new();
+        let mut current = self.receiver.recv().await;
+        while let Some(command) = current.take() {
+            let next = match command {
+                Command::Save(record) => {
+                    bulk.push(record);
+                    self.fill_bulk(&mut bulk).await
+                }
+                Command::Load(sender, query) => {
+                    // A bulk which is still waiting to be flushed must hit the persistence
+                    // backend before we answer the load, so readers never miss records which have
+                    // already been accepted by `save`.
+                    if !bulk.is_empty() {
+                        self.storage.save(&mut bulk).await;
+                        bulk.clear();
+                    }
+                    // Fetch records ...
+                    let records = self.storage.load(query).await;
+                    // ... and answer sender. This might fail, but if the sender is dropped and
+                    // stopped, caring, so do we. Let's drop the result.
+                    let _ = sender.send(records);
+                    // We did not peek ahead, so we do not know the next command.
+                    None
+                }
+                Command::Stream(sink, query) => {
+                    // Same reasoning as for `Command::Load`: flush before serving, so the stream
+                    // never misses records which have already been accepted by `save`.
+                    if !bulk.is_empty() {
+                        self.storage.save(&mut bulk).await;
+                        bulk.clear();
+                    }
+                    // `Storage::load_stream` sends into an unbounded channel (see its docs for
+                    // why), so this call itself never blocks on a slow or paused stream consumer.
+                    // A spawned task relays from there into the bounded `sink` the caller actually
+                    // sees, so that task (not the actor) is the one left waiting if the consumer
+                    // stalls.
+                    let (relay_sender, relay_receiver) = unbounded_channel();
+                    tokio::spawn(relay_to_sink(relay_receiver, sink));
+                    self.storage.load_stream(query, relay_sender).await;
+                    None
+                }
+                Command::Flush(sender) => {
+                    // Same reasoning as for `Command::Load`: resolving the oneshot only once the
+                    // current bulk has hit the storage backend is what makes this a checkpoint.
+                    if !bulk.is_empty() {
+                        self.storage.save(&mut bulk).await;
+                        bulk.clear();
+                    }
+                    // If the caller stopped waiting for the checkpoint, so do we.
+                    let _ = sender.send(());
+                    None
+                }
+            };
+            // Use next or wait for next event
+            current = if next.is_none() {
+                // Wait for the next event, can block. If none this means recorder has been dropped
+                // and we terminate this loop.
+                self.receiver.recv().await
+            } else {
+                // We already know the next event to process, since we had to peek ahead.
+                next
+            };
+        }
+        self.storage
+    }
+
+    /// Buffers records into `bulk` (which already holds at least one record) according to
+    /// `self.policy` (see [`fill_bulk`]), then flushes `bulk` to the storage backend. Returns the
+    /// next command to process, if one was already received while waiting for `bulk` to fill up.
+    async fn fill_bulk(&mut self, bulk: &mut Vec<T::Record>) -> Option<Command<T>> {
+        let next = fill_bulk(bulk, &self.policy, &mut self.receiver).await;
+        self.storage.save(bulk).await;
+        bulk.clear();
+        next
+    }
+}
+
+impl<T: Storage> Batched for Command<T> {
+    type Record = T::Record;
+
+    fn into_record(self) -> Result<T::Record, Self> {
+        match self {
+            Command::Save(record) => Ok(record),
+            other => Err(other),
+        }
+    }
+}
+
+impl<T: Storage> Recv<Command<T>> for CommandReceiver<T> {
+    async fn recv(&mut self) -> Option<Command<T>> {
+        CommandReceiver::recv(self).await
+    }
+}
+
+/// Either half of the two kinds of channels a [`Recorder`] can be backed by.
+enum CommandSender<T: Storage> {
+    /// Used by [`Recorder::new`] and [`Recorder::with_lazy_storage`]. Never blocks nor fails to
+    /// enqueue a command, at the cost of growing memory without bound if the actor can not keep up.
+    Unbounded(UnboundedSender<Command<T>>),
+    /// Used by [`Recorder::with_capacity`]. Bounds the number of commands buffered between the
+    /// recorder and its actor, so the caller can be made to wait instead.
+    Bounded(Sender<Command<T>>),
+}
+
+/// Counterpart of [`CommandSender`], held by the [`Actor`].
+enum CommandReceiver<T: Storage> {
+    Unbounded(UnboundedReceiver<Command<T>>),
+    Bounded(Receiver<Command<T>>),
+}
+
+impl<T: Storage> CommandReceiver<T> {
+    async fn recv(&mut self) -> Option<Command<T>> {
+        match self {
+            CommandReceiver::Unbounded(receiver) => receiver.recv().await,
+            CommandReceiver::Bounded(receiver) => receiver.recv().await,
+        }
+    }
+}
+
+/// Message send from recorder to actor. Allowes for custom debug implementation lifting the
+/// limitation that `T` has to be `Debug`.
+enum Command<T: Storage> {
+    /// Save record T to the storage backend
+    Save(T::Record),
+    /// Load all records from the storage. Use the sender to return them back to the caller.
+    Load(oneshot::Sender<Vec<T::Record>>, T::Query),
+    /// Stream records from the storage. Use the sink to send matching records as they are found.
+    Stream(Sender<T::Record>, T::Query),
+    /// Persist the current bulk, then signal completion via the sender. Used by
+    /// [`Recorder::flush`] to give callers a durability checkpoint.
+    Flush(oneshot::Sender<()>),
+}
+
+/// Custom implementation of debug for Message, which does not rely on the record type `T` to be
+/// debug itstelf.
+impl<T> std::fmt::Debug for Command<T>
+where
+    T: Storage,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::Save(_) => f.debug_tuple("Save").finish(),
+            Command::Load(..) => f.debug_tuple("Load").finish(),
+            Command::Stream(..) => f.debug_tuple("Stream").finish(),
+            Command::Flush(_) => f.debug_tuple("Flush").finish(),
+        }
+    }
+}
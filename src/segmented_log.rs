@@ -0,0 +1,365 @@
+use crate::Storage;
+use async_trait::async_trait;
+use std::{
+    io::SeekFrom,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::mpsc::UnboundedSender,
+    time::sleep,
+};
+
+/// Number of attempts a steady-state disk operation is retried before giving up.
+const IO_RETRY_ATTEMPTS: usize = 3;
+/// Delay between retries of a steady-state disk operation.
+const IO_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Retries a fallible disk operation a few times with a short delay in between, so a transient
+/// I/O error does not immediately tear down the actor using this [`SegmentedLog`].
+///
+/// [`Storage`] is infallible by design (see its documentation), leaving implementations to decide
+/// how to handle errors. [`SegmentedLog`] chooses to retry steady-state operations a few times
+/// before panicking, rather than the other extreme of panicking on the very first I/O hiccup.
+///
+/// A macro rather than a generic function, since `$op` borrows from its surrounding scope (e.g.
+/// `self.active.data`) and is re-evaluated on every retry; threading that borrow through a
+/// closure runs into the closure's captures not being allowed to outlive an individual call.
+macro_rules! retry_io {
+    ($op:expr) => {{
+        let mut attempt = 0;
+        loop {
+            match $op.await {
+                Ok(value) => break value,
+                Err(error) => {
+                    attempt += 1;
+                    if attempt >= IO_RETRY_ATTEMPTS {
+                        panic!(
+                            "segmented log disk operation failed after {IO_RETRY_ATTEMPTS} attempts: {error}"
+                        );
+                    }
+                    sleep(IO_RETRY_DELAY).await;
+                }
+            }
+        }
+    }};
+}
+
+/// Persists records to disk as an append-only log, split across fixed-size segment files.
+///
+/// Each record is assigned a monotonically increasing, logical `offset`, decoupled from
+/// wall-clock time: the first record ever saved gets offset `0`, the second `1`, and so on.
+/// Records are appended to an active segment, a pair of files `data.<base_offset>` (the
+/// serialized records, length-prefixed) and `index.<base_offset>` (mapping each offset within the
+/// segment to the byte position of its record within the data file), where `<base_offset>` is the
+/// offset of the first record stored in that segment. Once the active data segment has reached
+/// `segment_bytes_threshold`, both files are fsynced and a new segment is started before the next
+/// record is appended, with a base offset equal to the next offset to be written.
+///
+/// Since [`Storage::Record`] does not require a particular serialization format, you bring your
+/// own by supplying an `encode` and a `decode` closure.
+///
+/// On [`SegmentedLog::open`], a segment whose trailing record was not fully written (e.g. because
+/// the process was killed mid-write) is recovered by discarding that incomplete tail, rather than
+/// failing to open.
+pub struct SegmentedLog<T> {
+    directory: PathBuf,
+    segment_bytes_threshold: u64,
+    active: ActiveSegment,
+    encode: Encode<T>,
+    decode: Decode<T>,
+}
+
+type Encode<T> = Box<dyn Fn(&T) -> Vec<u8> + Send + Sync>;
+type Decode<T> = Box<dyn Fn(&[u8]) -> T + Send + Sync>;
+
+impl<T> SegmentedLog<T> {
+    /// Opens (creating if necessary) a segmented log rooted at `directory`.
+    ///
+    /// `segment_bytes_threshold` is the size in bytes of serialized records (excluding index
+    /// overhead) after which the active segment is rolled over into a new one.
+    pub async fn open(
+        directory: impl Into<PathBuf>,
+        segment_bytes_threshold: u64,
+        encode: impl Fn(&T) -> Vec<u8> + Send + Sync + 'static,
+        decode: impl Fn(&[u8]) -> T + Send + Sync + 'static,
+    ) -> Self {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)
+            .await
+            .expect("segmented log directory must be creatable");
+        let base_offsets = discover_base_offsets(&directory).await;
+        let active_base_offset = base_offsets.last().copied().unwrap_or(0);
+        let active = ActiveSegment::open(&directory, active_base_offset).await;
+        Self {
+            directory,
+            segment_bytes_threshold,
+            active,
+            encode: Box::new(encode),
+            decode: Box::new(decode),
+        }
+    }
+
+    async fn append_one(&mut self, payload: Vec<u8>) {
+        let position = self.active.data_len;
+        retry_io!(self.active.index.write_all(&position.to_le_bytes()));
+        retry_io!(self
+            .active
+            .data
+            .write_all(&(payload.len() as u32).to_le_bytes()));
+        retry_io!(self.active.data.write_all(&payload));
+        self.active.data_len += RECORD_HEADER_LEN + payload.len() as u64;
+        self.active.next_offset += 1;
+    }
+
+    /// Fsyncs the active segment, then starts a new one whose base offset is the next offset to
+    /// be written.
+    async fn roll_over(&mut self) {
+        self.sync_active().await;
+        self.active = ActiveSegment::open(&self.directory, self.active.next_offset).await;
+    }
+
+    async fn sync_active(&mut self) {
+        retry_io!(self.active.data.sync_all());
+        retry_io!(self.active.index.sync_all());
+    }
+}
+
+#[async_trait]
+impl<T> Storage for SegmentedLog<T>
+where
+    T: Send,
+{
+    type Record = T;
+    type Query = Range<u64>;
+
+    async fn save(&mut self, records: &mut Vec<T>) {
+        for record in records.drain(..) {
+            let payload = (self.encode)(&record);
+            if self.active.data_len >= self.segment_bytes_threshold {
+                self.roll_over().await;
+            }
+            self.append_one(payload).await;
+        }
+        self.sync_active().await;
+    }
+
+    async fn load(&mut self, query: Range<u64>) -> Vec<T> {
+        let mut records = Vec::new();
+        for segment in self.segments_covering(&query).await {
+            segment
+                .for_each_payload(|payload| {
+                    records.push((self.decode)(payload));
+                    true
+                })
+                .await;
+        }
+        records
+    }
+
+    /// Streams matching records segment by segment, and within a segment record by record,
+    /// rather than loading the whole query result into memory upfront. Records the receiving end
+    /// of `sink` has not taken yet stay buffered in that unbounded channel.
+    async fn load_stream(&mut self, query: Range<u64>, sink: UnboundedSender<T>) {
+        for segment in self.segments_covering(&query).await {
+            let mut stopped = false;
+            segment
+                .for_each_payload(|payload| {
+                    if sink.send((self.decode)(payload)).is_err() {
+                        stopped = true;
+                    }
+                    !stopped
+                })
+                .await;
+            if stopped {
+                return;
+            }
+        }
+    }
+}
+
+impl<T> SegmentedLog<T> {
+    /// Locates, for every segment overlapping `query`, the matching records, without reading any
+    /// of them into memory.
+    async fn segments_covering(&mut self, query: &Range<u64>) -> Vec<SegmentRange> {
+        if query.start >= query.end {
+            return Vec::new();
+        }
+        let base_offsets = discover_base_offsets(&self.directory).await;
+        let mut segments = Vec::new();
+        for (position, &base_offset) in base_offsets.iter().enumerate() {
+            let segment_end = if base_offset == self.active.base_offset {
+                self.active.next_offset
+            } else {
+                base_offsets[position + 1]
+            };
+            if segment_end <= query.start || base_offset >= query.end {
+                continue;
+            }
+            let from = query.start.max(base_offset) - base_offset;
+            let to = query.end.min(segment_end) - base_offset;
+            segments.push(SegmentRange {
+                index_path: segment_path(&self.directory, INDEX_PREFIX, base_offset),
+                data_path: segment_path(&self.directory, DATA_PREFIX, base_offset),
+                range: from..to,
+            });
+        }
+        segments
+    }
+}
+
+/// Identifies the matching records of one segment by their byte positions on disk, rather than
+/// holding the segment's files in memory.
+struct SegmentRange {
+    index_path: PathBuf,
+    data_path: PathBuf,
+    /// Offsets within the segment (not within the log as a whole) of the matching records.
+    range: Range<u64>,
+}
+
+impl SegmentRange {
+    /// Seeks to, and reads, only the slice of the index file covering `self.range`, rather than
+    /// the whole file.
+    async fn positions(&self) -> Vec<u64> {
+        if self.range.start >= self.range.end {
+            return Vec::new();
+        }
+        let mut index = retry_io!(File::open(&self.index_path));
+        retry_io!(index.seek(SeekFrom::Start(self.range.start * 8)));
+        let mut buffer = vec![0u8; (self.range.end - self.range.start) as usize * 8];
+        retry_io!(index.read_exact(&mut buffer));
+        buffer
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    /// Reads and hands each matching record's payload to `on_record`, in offset order, seeking to
+    /// and reading only that one record's header and payload from the data file at a time, so no
+    /// more than one record is ever held in memory. Stops early if `on_record` returns `false`.
+    async fn for_each_payload(&self, mut on_record: impl FnMut(&[u8]) -> bool) {
+        let positions = self.positions().await;
+        if positions.is_empty() {
+            return;
+        }
+        let mut data = retry_io!(File::open(&self.data_path));
+        let mut payload = Vec::new();
+        for position in positions {
+            retry_io!(data.seek(SeekFrom::Start(position)));
+            let mut header = [0u8; RECORD_HEADER_LEN as usize];
+            retry_io!(data.read_exact(&mut header));
+            let len = u32::from_le_bytes(header) as usize;
+            payload.clear();
+            payload.resize(len, 0);
+            retry_io!(data.read_exact(&mut payload));
+            if !on_record(&payload) {
+                return;
+            }
+        }
+    }
+}
+
+/// Number of bytes used to prefix a record in the data file with its length.
+const RECORD_HEADER_LEN: u64 = 4;
+
+const DATA_PREFIX: &str = "data";
+const INDEX_PREFIX: &str = "index";
+
+fn segment_path(directory: &Path, prefix: &str, base_offset: u64) -> PathBuf {
+    directory.join(format!("{prefix}.{base_offset:020}"))
+}
+
+/// Offsets of all segments found in `directory`, ascending.
+async fn discover_base_offsets(directory: &Path) -> Vec<u64> {
+    let mut entries = retry_io!(fs::read_dir(directory));
+    let mut base_offsets = Vec::new();
+    while let Some(entry) = retry_io!(entries.next_entry()) {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if let Some(suffix) = name.strip_prefix("data.")
+            && let Ok(base_offset) = suffix.parse()
+        {
+            base_offsets.push(base_offset);
+        }
+    }
+    base_offsets.sort_unstable();
+    base_offsets
+}
+
+/// The segment currently being appended to.
+struct ActiveSegment {
+    base_offset: u64,
+    next_offset: u64,
+    data: File,
+    index: File,
+    /// Number of bytes of fully written records in `data`.
+    data_len: u64,
+}
+
+impl ActiveSegment {
+    /// Opens the segment with the given base offset, creating it if it does not exist yet. Any
+    /// trailing record which was not fully written to disk (recognizable because its length
+    /// prefix, or its payload, got truncated) is discarded, along with its index entry.
+    async fn open(directory: &Path, base_offset: u64) -> Self {
+        let mut index = retry_io!(OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(segment_path(directory, INDEX_PREFIX, base_offset)));
+        let mut data = retry_io!(OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(segment_path(directory, DATA_PREFIX, base_offset)));
+
+        let mut index_bytes = Vec::new();
+        retry_io!(index.read_to_end(&mut index_bytes));
+        let mut data_bytes = Vec::new();
+        retry_io!(data.read_to_end(&mut data_bytes));
+
+        let mut positions: Vec<u64> = index_bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        // Drop indexed records whose header or payload did not make it fully to disk.
+        while let Some(&position) = positions.last() {
+            if complete_record_end(&data_bytes, position).is_some() {
+                break;
+            }
+            positions.pop();
+        }
+        let data_len = positions
+            .last()
+            .map_or(0, |&position| complete_record_end(&data_bytes, position).unwrap());
+
+        retry_io!(data.set_len(data_len));
+        retry_io!(data.seek(SeekFrom::End(0)));
+        retry_io!(index.set_len(positions.len() as u64 * 8));
+        retry_io!(index.seek(SeekFrom::End(0)));
+
+        Self {
+            base_offset,
+            next_offset: base_offset + positions.len() as u64,
+            data,
+            index,
+            data_len,
+        }
+    }
+}
+
+/// Byte position right after the record starting at `position` in `data`, if that record (header
+/// and payload) is fully present.
+fn complete_record_end(data: &[u8], position: u64) -> Option<u64> {
+    let position = position as usize;
+    let header_end = position.checked_add(RECORD_HEADER_LEN as usize)?;
+    let header: [u8; 4] = data.get(position..header_end)?.try_into().ok()?;
+    let len = u32::from_le_bytes(header) as usize;
+    let record_end = header_end.checked_add(len)?;
+    if record_end > data.len() {
+        return None;
+    }
+    Some(record_end as u64)
+}
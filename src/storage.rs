@@ -1,6 +1,7 @@
 use std::ops::Range;
 
 use async_trait::async_trait;
+use tokio::sync::mpsc::UnboundedSender;
 
 /// Can save records asynchronously
 #[async_trait]
@@ -26,6 +27,34 @@ pub trait Storage {
 
     /// Load the contents of the storage as a list of records.
     async fn load(&mut self, query: Self::Query) -> Vec<Self::Record>;
+
+    /// Loads the records matching `query`, sending each one to `sink` as it becomes available.
+    ///
+    /// The default simply calls [`Storage::load`] and forwards the whole result afterwards.
+    /// Override this for backends which can produce matching records incrementally (e.g. while
+    /// scanning a file on disk), so that a caller using [`crate::Recorder::stream`] can start
+    /// processing before the whole query result is available.
+    ///
+    /// `sink` is unbounded on purpose: [`crate::Recorder`] runs its actor single-threaded, and this
+    /// method is called directly from that actor's command loop, so a `send` which could block on
+    /// backpressure would stall every other pending command (`save`, `records`, `flush`, ...) for
+    /// as long as whatever is downstream of `sink` takes to catch up. `sink` is actually the
+    /// sending half of an internal channel; a separate task relays from there into the bounded
+    /// stream a caller of [`crate::Recorder::stream`] actually sees, so a slow or paused consumer
+    /// only ever blocks that relay task. Records the relay task has not forwarded yet stay
+    /// buffered in memory in `sink`'s channel, without any bound on their number.
+    async fn load_stream(&mut self, query: Self::Query, sink: UnboundedSender<Self::Record>)
+    where
+        Self::Record: Send,
+        Self::Query: Send,
+    {
+        for record in self.load(query).await {
+            if sink.send(record).is_err() {
+                // The receiving end lost interest, no point producing further records.
+                break;
+            }
+        }
+    }
 }
 
 /// This implementation is usefull for using as a fake for testing. In production you are more
@@ -59,4 +88,41 @@ impl<Q, R> Storage for Box<dyn Storage<Query = Q, Record = R> + Send> where Q: S
     async fn load(&mut self, query: Q) -> Vec<R> {
         (**self).load(query).await
     }
+
+    async fn load_stream(&mut self, query: Q, sink: UnboundedSender<R>) {
+        // Forward to the wrapped storage's own `load_stream`, so a type-erased backend which
+        // overrides it (e.g. `SegmentedLog`) does not silently fall back to the default once
+        // boxed.
+        (**self).load_stream(query, sink).await
+    }
+}
+
+/// Can save records asynchronously, but may fail to do so.
+///
+/// Implement this instead of [`Storage`] if talking to your persistence backend can fail, e.g.
+/// because of a flaky network connection. Unlike `Storage`, whose documentation pushes retry
+/// logic into every implementation, a failure returned from [`TryStorage::save`] is retried by
+/// [`crate::TryRecorder`] itself, following a configurable retry policy, so implementations only
+/// have to report what went wrong, not when to give up. A failure returned from
+/// [`TryStorage::load`] is not retried; it is handed to the caller of
+/// [`crate::TryRecorder::records`] as is.
+#[async_trait]
+pub trait TryStorage {
+    /// Records saved in the storage
+    type Record;
+
+    /// Describes the desired data for the load operation. Usefull for e.g. applying filters.
+    type Query;
+
+    /// Describes what went wrong trying to save or load records.
+    type Error;
+
+    /// Saves all the records to the persistence backend. See [`Storage::save`] for why `records`
+    /// is a `&mut Vec` rather than a `&[Self::Record]`. On success, implementations must drain
+    /// `records` (e.g. via [`Vec::append`]), mirroring [`Storage::save`]. On failure, `records`
+    /// must be left untouched, so the caller can retry the exact same bulk.
+    async fn save(&mut self, records: &mut Vec<Self::Record>) -> Result<(), Self::Error>;
+
+    /// Load the contents of the storage as a list of records.
+    async fn load(&mut self, query: Self::Query) -> Result<Vec<Self::Record>, Self::Error>;
 }
@@ -0,0 +1,320 @@
+use crate::{
+    batching::{fill_bulk, Batched, BatchPolicy, Recv},
+    TryStorage,
+};
+use std::{
+    cell::Cell,
+    fmt::Formatter,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::JoinHandle,
+    time::sleep,
+};
+
+/// Persists records asynchronously, retrying transient failures on the caller's behalf.
+///
+/// Use this instead of [`crate::Recorder`] if your persistence backend implements [`TryStorage`]
+/// rather than [`crate::Storage`], i.e. if talking to it can fail. Like [`crate::Recorder`], it
+/// spawns an actor to which all records are send immediatly, but the actor also owns a
+/// [`RetryPolicy`] and retries a failed [`TryStorage::save`] with an exponential backoff before
+/// giving up. Should a bulk still fail after all attempts are exhausted, the actor reports the
+/// error through the [`UnboundedReceiver`] returned alongside the `TryRecorder`, instead of
+/// panicking, and keeps the bulk at the front of its internal buffer, so it is retried together
+/// with (and ordered before) whatever is saved afterwards.
+///
+/// Like [`crate::Recorder`], records are collected into bulks according to a [`BatchPolicy`]
+/// before being handed to the storage backend.
+pub struct TryRecorder<T: TryStorage> {
+    join_handle: JoinHandle<T>,
+    sender: UnboundedSender<Command<T>>,
+}
+
+impl<T> TryRecorder<T>
+where
+    T: TryStorage + 'static + Send,
+    T::Record: Send,
+    T::Query: Send,
+    T::Error: Send + Clone,
+{
+    /// Spawns the actor and returns the recorder together with the receiving end of its error
+    /// sink. Every bulk which still fails to persist after `retry_policy.max_attempts` attempts is
+    /// reported there.
+    pub fn new(
+        storage: T,
+        policy: BatchPolicy,
+        retry_policy: RetryPolicy,
+    ) -> (Self, UnboundedReceiver<T::Error>) {
+        let (sender, receiver) = unbounded_channel();
+        let (error_sink, errors) = unbounded_channel();
+        let actor = Actor::new(storage, receiver, policy, retry_policy, error_sink);
+        let join_handle = tokio::spawn(actor.run());
+        (
+            Self {
+                join_handle,
+                sender,
+            },
+            errors,
+        )
+    }
+
+    /// Sends the record to the internal actor for storage. This interface is fire and forget. It
+    /// will not wait for the record to be actually persisted, just place it in the channel for the
+    /// actor to pick up.
+    pub fn save(&self, record: T::Record) {
+        self.sender
+            .send(Command::Save(record))
+            .expect("Receiver must not be closed.")
+    }
+
+    /// Stop accepting new records to save, persist the ones send so far.
+    ///
+    /// Gives back ownership of the underlying storage.
+    pub async fn close(self) -> T {
+        drop(self.sender);
+        self.join_handle
+            .await
+            .expect("Recorder actor thread must always be able to join")
+    }
+
+    /// All the records stored in the internal storage.
+    pub async fn records(&self, query: T::Query) -> Result<Vec<T::Record>, T::Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::Load(sender, query))
+            .expect("Receiver must not be closed");
+        receiver.await.expect("The sender must not be dropped")
+    }
+}
+
+/// Configures how the actor behind a [`TryRecorder`] retries a bulk which failed to persist.
+///
+/// Attempts are spaced out using an exponentially growing delay (`base_delay * 2^attempt`, capped
+/// at `max_delay`) with full jitter, i.e. the actual delay is chosen uniformly between zero and
+/// that cap. This avoids many actors backing off in lockstep and hammering the persistence backend
+/// again at the same moment.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Give up retrying and report the error after this many attempts to save a bulk.
+    pub max_attempts: usize,
+    /// Scale of the backoff: the delay before the first retry is picked between zero and twice
+    /// this.
+    pub base_delay: Duration,
+    /// Upper bound for the delay between retries, regardless of how many attempts already failed.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Jittered backoff to wait before the attempt-th retry (`attempt` is 1 for the first retry).
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX));
+        let capped = exponential.min(self.max_delay);
+        full_jitter(capped)
+    }
+}
+
+/// Returns a duration picked uniformly at random between zero and `upper_bound`.
+///
+/// We do not want to pull in a dependency on a random number generator crate just for backoff
+/// jitter, so instead we keep a small xorshift generator per thread, seeded once from the current
+/// time and thread id. This is not suitable for anything security sensitive, but is good enough for
+/// avoiding a thundering herd of retries.
+fn full_jitter(upper_bound: Duration) -> Duration {
+    upper_bound.mul_f64(next_random_fraction())
+}
+
+/// A pseudo-random number in `0..1`, advancing a thread-local xorshift64 generator.
+fn next_random_fraction() -> f64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(seed_from_time_and_thread());
+    }
+    STATE.with(|state| {
+        // xorshift64, see Marsaglia, "Xorshift RNGs" (2003).
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
+
+/// Seeds a thread's xorshift generator from the current time and that thread's id, so distinct
+/// threads (and hence distinct actors) start out of step with each other.
+fn seed_from_time_and_thread() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time must not be before the unix epoch")
+        .as_nanos() as u64;
+    let thread_id = format!("{:?}", std::thread::current().id());
+    let seed = thread_id
+        .bytes()
+        .fold(nanos, |hash, byte| hash.wrapping_mul(31).wrapping_add(u64::from(byte)));
+    // xorshift64 never recovers from a zero state, so make sure we never start in it.
+    if seed == 0 {
+        1
+    } else {
+        seed
+    }
+}
+
+/// Asynchronously spawned by [`TryRecorder`] in order to persist records
+struct Actor<T: TryStorage> {
+    storage: T,
+    receiver: UnboundedReceiver<Command<T>>,
+    policy: BatchPolicy,
+    retry_policy: RetryPolicy,
+    error_sink: UnboundedSender<T::Error>,
+}
+
+impl<T> Actor<T>
+where
+    T: TryStorage,
+    T::Error: Clone,
+{
+    fn new(
+        storage: T,
+        receiver: UnboundedReceiver<Command<T>>,
+        policy: BatchPolicy,
+        retry_policy: RetryPolicy,
+        error_sink: UnboundedSender<T::Error>,
+    ) -> Self {
+        Self {
+            storage,
+            receiver,
+            policy,
+            retry_policy,
+            error_sink,
+        }
+    }
+
+    pub async fn run(mut self) -> T {
+        let mut bulk = Vec::new();
+        let mut current = self.receiver.recv().await;
+        while let Some(command) = current.take() {
+            let next = match command {
+                Command::Save(record) => {
+                    bulk.push(record);
+                    // Fold further immediatly (or, depending on `self.policy`, eventually)
+                    // available records into the same bulk before flushing, same as
+                    // `crate::Recorder`'s actor.
+                    let next = fill_bulk(&mut bulk, &self.policy, &mut self.receiver).await;
+                    let _ = self.flush(&mut bulk).await;
+                    next
+                }
+                Command::Load(sender, query) => {
+                    // A bulk which is still waiting to be flushed must hit the persistence backend
+                    // before we answer the load, so readers never miss records which have already
+                    // been accepted by `save`. If that flush permanently fails, the records it was
+                    // trying to persist are still only in `bulk`, not in the storage: answering
+                    // from `self.storage.load` regardless would silently omit them, so we report
+                    // the flush's error to this load instead of pretending it succeeded.
+                    //
+                    // Important: this arm must still evaluate to the next command to process (or
+                    // `None`), same as every other arm. A `continue` here would skip the
+                    // `current = ...` bookkeeping below and make the actor believe it was told to
+                    // shut down, permanently ending `run` instead of just answering this one load.
+                    if !bulk.is_empty()
+                        && let Err(error) = self.flush(&mut bulk).await
+                    {
+                        let _ = sender.send(Err(error));
+                        None
+                    } else {
+                        let records = self.storage.load(query).await;
+                        let _ = sender.send(records);
+                        None
+                    }
+                }
+            };
+            current = if next.is_none() {
+                self.receiver.recv().await
+            } else {
+                next
+            };
+        }
+        // A bulk left over from a permanently failed flush gets one more round of attempts before
+        // the storage is handed back.
+        if !bulk.is_empty() {
+            let _ = self.flush(&mut bulk).await;
+        }
+        self.storage
+    }
+
+    /// Persists `bulk`, retrying according to `self.retry_policy` as long as [`TryStorage::save`]
+    /// keeps failing. Clears `bulk` on success. If every attempt fails, reports the last error
+    /// through `self.error_sink`, leaves `bulk` untouched (so the unsaved records stay at the front
+    /// of the buffer: ordered before, and retried together with, whatever is saved next), and
+    /// returns that error so a caller waiting on this particular flush (e.g. a pending
+    /// [`Command::Load`]) can learn its answer would otherwise be missing records.
+    async fn flush(&mut self, bulk: &mut Vec<T::Record>) -> Result<(), T::Error> {
+        let mut attempt = 0;
+        loop {
+            match self.storage.save(bulk).await {
+                Ok(()) => return Ok(()),
+                Err(error) => {
+                    attempt += 1;
+                    if attempt >= self.retry_policy.max_attempts {
+                        // The receiving end is allowed to lose interest in errors, we must not
+                        // panic just because nobody is listening anymore.
+                        let _ = self.error_sink.send(error.clone());
+                        return Err(error);
+                    }
+                    sleep(self.retry_policy.backoff(attempt as u32)).await;
+                }
+            }
+        }
+    }
+}
+
+/// Message send from [`TryRecorder`] to its actor.
+enum Command<T: TryStorage> {
+    Save(T::Record),
+    Load(oneshot::Sender<Result<Vec<T::Record>, T::Error>>, T::Query),
+}
+
+impl<T: TryStorage> Batched for Command<T> {
+    type Record = T::Record;
+
+    fn into_record(self) -> Result<T::Record, Self> {
+        match self {
+            Command::Save(record) => Ok(record),
+            other => Err(other),
+        }
+    }
+}
+
+impl<T: TryStorage> Recv<Command<T>> for UnboundedReceiver<Command<T>> {
+    async fn recv(&mut self) -> Option<Command<T>> {
+        UnboundedReceiver::recv(self).await
+    }
+}
+
+/// Custom implementation of debug for Command, which does not rely on the record type `T` to be
+/// debug itstelf.
+impl<T> std::fmt::Debug for Command<T>
+where
+    T: TryStorage,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::Save(_) => f.debug_tuple("Save").finish(),
+            Command::Load(..) => f.debug_tuple("Load").finish(),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::{sync::Arc, time::Duration};
+
+use async_recorder::{BatchPolicy, Recorder};
+use async_trait::async_trait;
+use async_recorder::Storage;
+use tokio::sync::Mutex;
+
+#[tokio::test]
+async fn flushes_as_soon_as_max_records_is_reached() {
+    let bulks = Arc::new(Mutex::new(Vec::new()));
+    let storage = BulkSpy::new(bulks.clone());
+    let policy = BatchPolicy {
+        max_records: 2,
+        max_delay: Duration::from_secs(60),
+    };
+
+    let recorder = Recorder::new(storage, policy).await;
+    recorder.save("first");
+    recorder.save("second");
+    recorder.save("third");
+    let _ = recorder.close().await;
+
+    let bulks = bulks.lock().await;
+    assert_eq!(bulks.as_slice(), [vec!["first", "second"], vec!["third"]]);
+}
+
+#[tokio::test]
+async fn flushes_after_max_delay_even_if_max_records_is_not_reached() {
+    let bulks = Arc::new(Mutex::new(Vec::new()));
+    let storage = BulkSpy::new(bulks.clone());
+    let policy = BatchPolicy {
+        max_records: usize::MAX,
+        max_delay: Duration::from_millis(20),
+    };
+
+    let recorder = Recorder::new(storage, policy).await;
+    recorder.save("first");
+    // Give the actor's deadline time to elapse before we check anything.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    recorder.save("second");
+    let _ = recorder.close().await;
+
+    let bulks = bulks.lock().await;
+    assert_eq!(bulks.as_slice(), [vec!["first"], vec!["second"]]);
+}
+
+/// Records every bulk it is asked to save, without actually persisting it anywhere else.
+struct BulkSpy<T> {
+    bulks: Arc<Mutex<Vec<Vec<T>>>>,
+}
+
+impl<T> BulkSpy<T> {
+    fn new(bulks: Arc<Mutex<Vec<Vec<T>>>>) -> Self {
+        BulkSpy { bulks }
+    }
+}
+
+#[async_trait]
+impl<T> Storage for BulkSpy<T>
+where
+    T: Send,
+{
+    type Record = T;
+    type Query = ();
+
+    async fn save(&mut self, records: &mut Vec<T>) {
+        let bulk = std::mem::take(records);
+        self.bulks.lock().await.push(bulk);
+    }
+
+    async fn load(&mut self, _query: ()) -> Vec<T> {
+        Vec::new()
+    }
+}
@@ -0,0 +1,32 @@
+use async_recorder::{BatchPolicy, Recorder, TrySaveError};
+
+#[tokio::test]
+async fn try_save_hands_the_record_back_once_the_channel_is_full() {
+    let storage: Vec<i32> = Vec::new();
+    // The test runtime is single-threaded and nothing below yields to the actor before
+    // `try_save`, so the first record still occupies the single command slot at that point.
+    let policy = BatchPolicy {
+        max_records: usize::MAX,
+        max_delay: std::time::Duration::from_secs(60),
+    };
+    let recorder = Recorder::with_capacity(storage, 1, policy).await;
+
+    recorder.save_async(1).await;
+    match recorder.try_save(2) {
+        Err(TrySaveError::Full(record)) => assert_eq!(record, 2),
+        Ok(()) => panic!("expected the channel to already be full"),
+    }
+}
+
+#[tokio::test]
+async fn save_async_suspends_until_there_is_room_in_the_channel() {
+    let storage: Vec<i32> = Vec::new();
+    let recorder = Recorder::with_capacity(storage, 4, BatchPolicy::default()).await;
+
+    for record in 0..10 {
+        recorder.save_async(record).await;
+    }
+    let storage = recorder.close().await;
+
+    assert_eq!(storage, (0..10).collect::<Vec<_>>());
+}
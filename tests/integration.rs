@@ -1,6 +1,6 @@
 use std::{mem::swap, ops::Range, sync::Arc};
 
-use async_recorder::{Recorder, Storage};
+use async_recorder::{BatchPolicy, Recorder, Storage};
 use async_trait::async_trait;
 use tokio::sync::Mutex;
 
@@ -9,7 +9,7 @@ async fn record_event_to_persistence_backend() {
     let record = "Hello, World!";
     let storage = Vec::new();
 
-    let recorder = Recorder::new(storage);
+    let recorder = Recorder::new(storage, BatchPolicy::default()).await;
     recorder.save(record);
     let storage = recorder.close().await;
 
@@ -21,7 +21,7 @@ async fn persist_events_in_bulk() {
     let bulks = Arc::new(Mutex::new(Vec::new()));
     let storage = BlockableStorageSpy::new(bulks.clone());
 
-    let recorder = Recorder::new(storage);
+    let recorder = Recorder::new(storage, BatchPolicy::default()).await;
     {
         // Keep guard to bulks, so spy can not persist until it is cleared
         let _guard = bulks.lock().await;
@@ -42,7 +42,7 @@ async fn persist_events_in_bulk() {
 async fn records_are_filtered_using_query() {
     let storage = Vec::new();
 
-    let recorder = Recorder::new(storage);
+    let recorder = Recorder::new(storage, BatchPolicy::default()).await;
     // We write three records
     recorder.save("first");
     recorder.save("second");
@@ -61,7 +61,7 @@ async fn records_are_filtered_using_query() {
 async fn recorder_instantiation_does_not_need_to_wait_for_persistence_backend() {
     let lazy_storage = async { vec!["first"] };
 
-    let recorder = Recorder::from_delayed_storage(lazy_storage);
+    let recorder = Recorder::with_lazy_storage(lazy_storage, BatchPolicy::default());
     recorder.save("second");
     let records = recorder.records(0..2).await;
     let _ = recorder.close().await;
@@ -75,7 +75,7 @@ async fn recorder_should_be_able_to_work_with_a_storage_decided_at_runtime() {
         dyn Storage<Record = &'static str, Query = Range<usize>> + Send + 'static,
     > = Box::<Vec<&str>>::default();
 
-    let recoder = Recorder::new(dynamic_storage);
+    let recoder = Recorder::new(dynamic_storage, BatchPolicy::default()).await;
     recoder.save("Hello, World!");
     let first_record = recoder.records(0..1).await;
 
@@ -0,0 +1,114 @@
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_recorder::{BatchPolicy, Recorder, Storage};
+use async_trait::async_trait;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn stream_yields_records_matching_the_query() {
+    let storage: Vec<i32> = (0..5).collect();
+    let recorder = Recorder::new(storage, BatchPolicy::default()).await;
+
+    let collected: Vec<i32> = recorder.stream(1..4).await.collect().await;
+
+    assert_eq!(collected, vec![1, 2, 3]);
+}
+
+#[tokio::test]
+async fn a_stalled_stream_consumer_does_not_block_other_commands() {
+    let storage: Vec<i32> = (0..5).collect();
+    let recorder = Recorder::new(storage, BatchPolicy::default()).await;
+
+    // Start a stream but never poll it further than acquiring the first item.
+    let mut stream = Box::pin(recorder.stream(0..5).await);
+    assert_eq!(stream.next().await, Some(0));
+
+    // The actor must still be responsive to unrelated commands while the stream consumer sits
+    // idle.
+    let records = tokio::time::timeout(Duration::from_millis(500), recorder.records(0..5))
+        .await
+        .expect("records() must not be blocked by the idle stream consumer");
+    assert_eq!(records, vec![0, 1, 2, 3, 4]);
+}
+
+#[tokio::test]
+async fn flush_resolves_only_after_pending_records_are_persisted() {
+    let storage: Vec<i32> = Vec::new();
+    let policy = BatchPolicy {
+        max_records: usize::MAX,
+        max_delay: Duration::from_secs(60),
+    };
+    let recorder = Recorder::new(storage, policy).await;
+
+    recorder.save(1);
+    recorder.save(2);
+    recorder.flush().await;
+
+    let storage = recorder.close().await;
+    assert_eq!(storage, vec![1, 2]);
+}
+
+#[tokio::test]
+async fn boxing_a_storage_does_not_lose_its_load_stream_override() {
+    let load_calls = Arc::new(AtomicUsize::new(0));
+    let load_stream_calls = Arc::new(AtomicUsize::new(0));
+    let storage: Box<dyn Storage<Query = Range<usize>, Record = i32> + Send> =
+        Box::new(SpyStorage {
+            records: (0..5).collect(),
+            load_calls: load_calls.clone(),
+            load_stream_calls: load_stream_calls.clone(),
+        });
+    let recorder = Recorder::new(storage, BatchPolicy::default()).await;
+
+    let collected: Vec<i32> = recorder.stream(1..4).await.collect().await;
+    let _ = recorder.close().await;
+
+    assert_eq!(collected, vec![1, 2, 3]);
+    // If the boxed `dyn Storage` silently fell back to the default `load_stream`, it would have
+    // gone through `load` instead and this would be `(0, 1)`.
+    assert_eq!(
+        (load_stream_calls.load(Ordering::SeqCst), load_calls.load(Ordering::SeqCst)),
+        (1, 0)
+    );
+}
+
+/// A [`Storage`] which overrides [`Storage::load_stream`] and counts how many times each of
+/// `load` and `load_stream` was actually called, so tests can tell which one a caller went
+/// through rather than just comparing the records they produced.
+struct SpyStorage {
+    records: Vec<i32>,
+    load_calls: Arc<AtomicUsize>,
+    load_stream_calls: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl Storage for SpyStorage {
+    type Query = Range<usize>;
+    type Record = i32;
+
+    async fn save(&mut self, records: &mut Vec<i32>) {
+        self.records.append(records);
+    }
+
+    async fn load(&mut self, query: Range<usize>) -> Vec<i32> {
+        self.load_calls.fetch_add(1, Ordering::SeqCst);
+        self.records[query].to_vec()
+    }
+
+    async fn load_stream(&mut self, query: Range<usize>, sink: UnboundedSender<i32>) {
+        self.load_stream_calls.fetch_add(1, Ordering::SeqCst);
+        for record in self.records[query].iter().copied() {
+            if sink.send(record).is_err() {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_recorder::{BatchPolicy, RetryPolicy, TryRecorder, TryStorage};
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+#[tokio::test]
+async fn retries_a_failing_save_before_giving_up() {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let storage = FailsNTimes {
+        attempts: attempts.clone(),
+        fail_first: 2,
+        records: Arc::new(Mutex::new(Vec::new())),
+    };
+    let retry_policy = RetryPolicy {
+        max_attempts: 5,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(1),
+    };
+
+    let (recorder, _errors) = TryRecorder::new(storage, BatchPolicy::default(), retry_policy);
+    recorder.save("first");
+    let records = recorder.records(0..1).await.unwrap();
+    let _ = recorder.close().await;
+
+    assert_eq!(records, vec!["first"]);
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn reports_a_bulk_through_the_error_sink_once_retries_are_exhausted() {
+    let retry_policy = RetryPolicy {
+        max_attempts: 2,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(1),
+    };
+
+    let (recorder, mut errors) = TryRecorder::new(AlwaysFails, BatchPolicy::default(), retry_policy);
+    recorder.save("first");
+    let error = errors.recv().await;
+    let _ = recorder.close().await;
+
+    assert_eq!(error, Some("boom"));
+}
+
+#[tokio::test]
+async fn the_actor_keeps_running_after_a_load_triggered_flush_fails() {
+    // Fails the first four attempts (two permanently-failing flushes at `max_attempts: 2` each),
+    // then starts accepting bulks.
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let storage = FailsNTimes {
+        attempts: attempts.clone(),
+        fail_first: 4,
+        records: Arc::new(Mutex::new(Vec::new())),
+    };
+    let retry_policy = RetryPolicy {
+        max_attempts: 2,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(1),
+    };
+
+    let (recorder, mut errors) = TryRecorder::new(storage, BatchPolicy::default(), retry_policy);
+
+    // The bulk's own flush (run as part of processing `Save`) exhausts its attempts and fails,
+    // leaving "first" un-persisted at the front of the bulk.
+    recorder.save("first");
+    assert_eq!(errors.recv().await, Some("boom"));
+
+    // `records` must retry that still-pending bulk before answering, so it hits the same
+    // permanent failure and reports it instead of serving a stale, empty load.
+    let result = recorder.records(0..1).await;
+    assert!(result.is_err());
+    assert_eq!(errors.recv().await, Some("boom"));
+
+    // The actor must still be alive and processing commands afterwards: reporting that second
+    // failure through `records` must not have ended the actor's run loop. A fresh save/records
+    // round trip, persisting both the recovered "first" and the new "second", must still succeed.
+    recorder.save("second");
+    let records = recorder.records(0..2).await.unwrap();
+    let _ = recorder.close().await;
+
+    assert_eq!(records, vec!["first", "second"]);
+}
+
+/// Fails the first `fail_first` attempts at saving a bulk, then starts accepting it.
+struct FailsNTimes {
+    attempts: Arc<AtomicUsize>,
+    fail_first: usize,
+    records: Arc<Mutex<Vec<&'static str>>>,
+}
+
+#[async_trait]
+impl TryStorage for FailsNTimes {
+    type Record = &'static str;
+    type Query = std::ops::Range<usize>;
+    type Error = &'static str;
+
+    async fn save(&mut self, records: &mut Vec<&'static str>) -> Result<(), &'static str> {
+        let attempt = self.attempts.fetch_add(1, Ordering::SeqCst);
+        if attempt < self.fail_first {
+            return Err("boom");
+        }
+        self.records.lock().await.append(records);
+        Ok(())
+    }
+
+    async fn load(
+        &mut self,
+        query: std::ops::Range<usize>,
+    ) -> Result<Vec<&'static str>, &'static str> {
+        Ok(self.records.lock().await[query].to_vec())
+    }
+}
+
+struct AlwaysFails;
+
+#[async_trait]
+impl TryStorage for AlwaysFails {
+    type Record = &'static str;
+    type Query = std::ops::Range<usize>;
+    type Error = &'static str;
+
+    async fn save(&mut self, _records: &mut Vec<&'static str>) -> Result<(), &'static str> {
+        Err("boom")
+    }
+
+    async fn load(
+        &mut self,
+        _query: std::ops::Range<usize>,
+    ) -> Result<Vec<&'static str>, &'static str> {
+        Ok(Vec::new())
+    }
+}